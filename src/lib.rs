@@ -0,0 +1,3 @@
+pub mod resample;
+pub mod stretch;
+pub mod wav_helper;
@@ -1,9 +1,13 @@
-use paulstretch_rust::stretch::paulstretch_multichannel;
+use paulstretch_rust::resample;
+use paulstretch_rust::stretch::{
+    paulstretch_multichannel, phase_vocoder_stretch_multichannel, PaulStretcher,
+};
 use paulstretch_rust::wav_helper;
 
 use clap::Parser;
 
 use std::io::Write;
+use std::path::Path;
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about)]
@@ -18,6 +22,45 @@ struct Args {
 
     #[clap(short, default_value_t = 0.25)]
     window_size_secs: f32,
+
+    /// Preserve phase across frames instead of randomizing it, for a clean,
+    /// artifact-free stretch at the cost of the usual paulstretch smear
+    #[clap(short, long)]
+    phase_coherent: bool,
+
+    /// Remix the input channels before stretching: `mono` (stereo->mono),
+    /// `stereo` (mono->stereo), `5.1-stereo`, or `reorder:0,1,...` for a
+    /// custom channel permutation
+    #[clap(long)]
+    remix: Option<String>,
+
+    /// Resample the stretched audio to this output sample rate
+    #[clap(long)]
+    output_rate: Option<u32>,
+
+    /// Shift pitch by this factor without changing duration, by resampling
+    /// the stretched audio and relabelling it at the original sample rate.
+    /// Pair with `-s` set to the same value to keep the stretch's own
+    /// duration change cancelled out
+    #[clap(long, default_value_t = 1.0)]
+    pitch_shift: f32,
+}
+
+fn parse_remix(preset: &str) -> wav_helper::RemixOp {
+    match preset {
+        "mono" => wav_helper::remix_presets::stereo_to_mono(),
+        "stereo" => wav_helper::remix_presets::mono_to_stereo(),
+        "5.1-stereo" => wav_helper::remix_presets::surround_5_1_to_stereo(),
+        other => {
+            if let Some(indices) = other.strip_prefix("reorder:") {
+                wav_helper::RemixOp::Reorder(
+                    indices.split(',').map(|i| i.parse().unwrap()).collect(),
+                )
+            } else {
+                panic!("unrecognised remix preset: {}", other);
+            }
+        }
+    }
 }
 
 fn print_progress(current: u32, total: u32) {
@@ -44,41 +87,192 @@ fn print_progress(current: u32, total: u32) {
     std::io::stdout().flush().unwrap();
 }
 
-fn main() {
-    let args = Args::parse();
+fn default_out_file(in_file: &str) -> String {
+    // export is WAV-only, so the default name always gets a .wav extension
+    // regardless of what the input container was
+    let in_path = Path::new(in_file);
+    let stem = in_path
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or(in_file);
+    in_path
+        .with_file_name(format!("{}-stretched.wav", stem))
+        .to_string_lossy()
+        .into_owned()
+}
 
-    let out_file = if let Some(out_file) = &args.out_file.as_deref() {
-        out_file.to_string()
-    } else {
-        args.in_file.replacen(".wav", "-stretched.wav", 1)
-    };
+// the streaming path below only has to handle the plain randomized-phase
+// stretch of a WAV file with no remix or resampling; anything fancier falls
+// back to `run_in_memory`, which still loads the whole track up front
+fn can_stream(args: &Args) -> bool {
+    !args.phase_coherent
+        && args.remix.is_none()
+        && args.output_rate.is_none()
+        && (args.pitch_shift - 1.0).abs() < f32::EPSILON
+        && Path::new(&args.in_file)
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|ext| ext.eq_ignore_ascii_case("wav"))
+            .unwrap_or(false)
+}
+
+// streams samples straight from hound's reader through one `PaulStretcher`
+// per channel and into hound's writer, so a long track is never fully
+// resident in memory the way `run_in_memory` has it
+fn run_streaming(args: &Args, out_file: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let mut reader = hound::WavReader::open(&args.in_file)?;
+    let spec = reader.spec();
+    let channels = spec.channels as usize;
+
+    println!(
+        "loaded {} (channels: {}, bit_depth: {}, sample_rate: {})",
+        &args.in_file, spec.channels, spec.bits_per_sample, spec.sample_rate
+    );
+    println!(
+        "processing (stretch factor: {}, window size: {}s)",
+        args.stretch_factor, args.window_size_secs
+    );
+
+    let mut stretchers: Vec<PaulStretcher> = (0..channels)
+        .map(|_| PaulStretcher::new(spec.sample_rate, args.window_size_secs, args.stretch_factor))
+        .collect();
+
+    let mut writer = hound::WavWriter::create(out_file, spec)?;
+    let mut samples = wav_helper::normalized_samples(&mut reader)?;
+
+    // read and process one block of interleaved frames at a time instead of
+    // the whole file, so peak memory stays bounded by block size
+    const BLOCK_FRAMES: usize = 4096;
+    let mut channel_blocks = vec![Vec::with_capacity(BLOCK_FRAMES); channels];
+
+    loop {
+        for channel in channel_blocks.iter_mut() {
+            channel.clear();
+        }
+
+        let mut frames_read = 0;
+        for _ in 0..BLOCK_FRAMES {
+            let mut frame = Vec::with_capacity(channels);
+            for _ in 0..channels {
+                match samples.next() {
+                    Some(s) => frame.push(s),
+                    None => break,
+                }
+            }
+
+            // a truncated trailing frame (sample count not divisible by
+            // `channels`) doesn't have a value for every channel; drop it
+            // rather than feed unequal-length blocks to the per-channel
+            // stretchers below
+            if frame.len() < channels {
+                break;
+            }
+
+            for (channel, s) in channel_blocks.iter_mut().zip(frame) {
+                channel.push(s);
+            }
+            frames_read += 1;
+        }
+
+        let produced: Vec<Vec<f32>> = channel_blocks
+            .iter()
+            .zip(stretchers.iter_mut())
+            .map(|(channel, stretcher)| stretcher.process_block(channel))
+            .collect();
+        write_interleaved(&mut writer, &spec, &produced)?;
 
-    let wave = wav_helper::load(&args.in_file).unwrap();
+        if frames_read < BLOCK_FRAMES {
+            break;
+        }
+    }
+
+    println!("done!");
+    println!("exporting {}", out_file);
+
+    let tails: Vec<Vec<f32>> = stretchers.iter_mut().map(|s| s.finish()).collect();
+    write_interleaved(&mut writer, &spec, &tails)?;
+    writer.finalize()?;
+    Ok(())
+}
+
+// each channel in `channel_blocks` holds the same number of newly-finished
+// samples; this writes them out frame-by-frame, interleaved, as hound expects
+fn write_interleaved(
+    writer: &mut hound::WavWriter<std::io::BufWriter<std::fs::File>>,
+    spec: &hound::WavSpec,
+    channel_blocks: &[Vec<f32>],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let num_frames = channel_blocks.first().map_or(0, |c| c.len());
+    for frame in 0..num_frames {
+        for channel in channel_blocks {
+            wav_helper::write_sample(writer, spec, channel[frame])?;
+        }
+    }
+    Ok(())
+}
+
+fn run_in_memory(args: &Args, out_file: &str) {
+    let mut wave = wav_helper::load(&args.in_file).unwrap();
 
     println!(
         "loaded {} (channels: {}, bit_depth: {}, sample_rate: {})",
         &args.in_file, wave.header.channels, wave.header.bit_depth, wave.header.sample_rate
     );
 
+    if let Some(preset) = &args.remix {
+        let op = parse_remix(preset);
+        wave.data = wav_helper::remix(&wave.data, &op).unwrap();
+        wave.header.channels = wave.data.len() as u16;
+        println!("remixed to {} channel(s)", wave.header.channels);
+    }
+
     println!(
         "processing (stretch factor: {}, window size: {}s)",
         args.stretch_factor, args.window_size_secs
     );
 
-    let stretched = paulstretch_multichannel(
-        wave.data,
-        wave.header.sample_rate,
-        args.window_size_secs,
-        args.stretch_factor,
-        &print_progress,
-    );
+    let mut stretched = if args.phase_coherent {
+        phase_vocoder_stretch_multichannel(
+            wave.data,
+            wave.header.sample_rate,
+            args.window_size_secs,
+            args.stretch_factor,
+            &print_progress,
+        )
+    } else {
+        paulstretch_multichannel(
+            wave.data,
+            wave.header.sample_rate,
+            args.window_size_secs,
+            args.stretch_factor,
+            &print_progress,
+        )
+    };
 
     println!("done!");
 
-    println!("exporting {}", &out_file);
+    let mut out_sample_rate = wave.header.sample_rate;
+
+    if (args.pitch_shift - 1.0).abs() > f32::EPSILON {
+        // resample the audio itself, but keep the sample_rate label as-is so
+        // playback runs pitch_shift times faster/slower, shifting the pitch
+        let shifted_rate = (out_sample_rate as f32 / args.pitch_shift).round() as u32;
+        stretched = resample::resample_multichannel(stretched, out_sample_rate, shifted_rate);
+        println!("pitch-shifted by {}x", args.pitch_shift);
+    }
+
+    if let Some(target_rate) = args.output_rate {
+        stretched = resample::resample_multichannel(stretched, out_sample_rate, target_rate);
+        out_sample_rate = target_rate;
+        println!("resampled to {}Hz", out_sample_rate);
+    }
+
+    wave.header.sample_rate = out_sample_rate;
+
+    println!("exporting {}", out_file);
 
     wav_helper::export(
-        &out_file,
+        out_file,
         wav_helper::Wave {
             header: wave.header,
             data: stretched,
@@ -86,3 +280,19 @@ fn main() {
     )
     .unwrap();
 }
+
+fn main() {
+    let args = Args::parse();
+
+    let out_file = if let Some(out_file) = &args.out_file {
+        out_file.to_string()
+    } else {
+        default_out_file(&args.in_file)
+    };
+
+    if can_stream(&args) {
+        run_streaming(&args, &out_file).unwrap();
+    } else {
+        run_in_memory(&args, &out_file);
+    }
+}
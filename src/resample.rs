@@ -0,0 +1,102 @@
+use std::f32::consts::PI;
+
+// how many input samples the sinc kernel reaches out to on either side;
+// bigger gives a sharper cutoff at the cost of more work per output sample
+const KERNEL_RADIUS: f32 = 8_f32;
+
+fn sinc(x: f32) -> f32 {
+    if x.abs() < 1e-8 {
+        1_f32
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+fn hann(x: f32, half_width: f32) -> f32 {
+    0.5 * (1_f32 + (PI * x / half_width).cos())
+}
+
+/// Resamples a single channel from `in_rate` to `out_rate` using a
+/// Hann-windowed sinc kernel, band-limiting the cutoff to `min(1, out/in)` so
+/// downsampling doesn't alias.
+pub fn resample(samples: &[f32], in_rate: u32, out_rate: u32) -> Vec<f32> {
+    if in_rate == out_rate || samples.is_empty() {
+        return samples.to_vec();
+    }
+
+    let ratio = out_rate as f32 / in_rate as f32;
+    let cutoff = ratio.min(1_f32);
+
+    let out_len = (samples.len() as f32 * ratio).round() as usize;
+    let mut output = Vec::with_capacity(out_len);
+
+    for n in 0..out_len {
+        let pos = n as f32 / ratio;
+        let lo = (pos - KERNEL_RADIUS).floor().max(0_f32) as usize;
+        let hi = (pos + KERNEL_RADIUS).ceil() as usize;
+        let hi = hi.min(samples.len().saturating_sub(1));
+
+        let mut acc = 0_f32;
+        for (i, sample) in samples.iter().enumerate().take(hi + 1).skip(lo) {
+            let dist = pos - i as f32;
+            if dist.abs() > KERNEL_RADIUS {
+                continue;
+            }
+            acc += sample * cutoff * sinc(cutoff * dist) * hann(dist, KERNEL_RADIUS);
+        }
+        output.push(acc);
+    }
+
+    output
+}
+
+/// Resamples every channel of a planar buffer from `in_rate` to `out_rate`.
+pub fn resample_multichannel(samples: Vec<Vec<f32>>, in_rate: u32, out_rate: u32) -> Vec<Vec<f32>> {
+    samples
+        .iter()
+        .map(|channel| resample(channel, in_rate, out_rate))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_rate_is_a_no_op() {
+        let samples = vec![0.1_f32, 0.2, -0.3, 0.4];
+        assert_eq!(resample(&samples, 44100, 44100), samples);
+    }
+
+    #[test]
+    fn upsampling_doubles_length() {
+        let samples = vec![0_f32; 1000];
+        let out = resample(&samples, 8000, 16000);
+        assert_eq!(out.len(), 2000);
+    }
+
+    #[test]
+    fn downsampling_halves_length() {
+        let samples = vec![0_f32; 1000];
+        let out = resample(&samples, 16000, 8000);
+        assert_eq!(out.len(), 500);
+    }
+
+    #[test]
+    fn preserves_a_dc_signal() {
+        let samples = vec![0.5_f32; 200];
+        let out = resample(&samples, 8000, 11025);
+        for s in &out[KERNEL_RADIUS as usize..out.len() - KERNEL_RADIUS as usize] {
+            assert!((s - 0.5).abs() < 0.05);
+        }
+    }
+
+    #[test]
+    fn multichannel_resamples_each_channel() {
+        let samples = vec![vec![0_f32; 100], vec![0_f32; 100]];
+        let out = resample_multichannel(samples, 8000, 16000);
+        assert_eq!(out.len(), 2);
+        assert_eq!(out[0].len(), 200);
+        assert_eq!(out[1].len(), 200);
+    }
+}
@@ -1,6 +1,10 @@
 use hound;
+use lewton::inside_ogg::OggStreamReader;
 
 use std::error::Error;
+use std::fs::File;
+use std::io::Read as _;
+use std::path::Path;
 
 #[derive(Debug)]
 pub enum Format {
@@ -22,7 +26,131 @@ pub struct Wave {
     pub data: Vec<Vec<f32>>,
 }
 
+#[derive(Debug, PartialEq, Eq)]
+enum Container {
+    Wav,
+    Ogg,
+}
+
+// picks the container by extension first, falling back to magic bytes for
+// files that were renamed or have no extension
+fn detect_container(path: &str) -> Result<Container, Box<dyn Error>> {
+    if let Some(ext) = Path::new(path).extension().and_then(|e| e.to_str()) {
+        match ext.to_ascii_lowercase().as_str() {
+            "wav" => return Ok(Container::Wav),
+            "ogg" | "oga" => return Ok(Container::Ogg),
+            _ => {}
+        }
+    }
+
+    let mut magic = [0_u8; 4];
+    File::open(path)?.read_exact(&mut magic)?;
+    match &magic {
+        b"RIFF" => Ok(Container::Wav),
+        b"OggS" => Ok(Container::Ogg),
+        _ => Err(format!("Unrecognised file format: {}", path).into()),
+    }
+}
+
+/// Loads a `Wave` from either a WAV or an Ogg Vorbis file, picked automatically.
 pub fn load(path: &str) -> Result<Wave, Box<dyn Error>> {
+    match detect_container(path)? {
+        Container::Wav => load_wav(path),
+        Container::Ogg => load_ogg(path),
+    }
+}
+
+// Ogg Vorbis is decode-only for now: `export()` still only writes WAV.
+fn load_ogg(path: &str) -> Result<Wave, Box<dyn Error>> {
+    let mut r = OggStreamReader::new(File::open(path)?)?;
+
+    let header = WaveHeader {
+        channels: r.ident_hdr.audio_channels as u16,
+        sample_rate: r.ident_hdr.audio_sample_rate,
+        bit_depth: 32,
+        format: Format::Float,
+    };
+
+    // lewton already hands back planar per-channel buffers, so there's no
+    // interleaved intermediate to go through like there is for hound
+    let mut data = vec![Vec::new(); header.channels as usize];
+    while let Some(packet) = r.read_dec_packet_generic::<Vec<Vec<f32>>>()? {
+        for (channel, samples) in data.iter_mut().zip(packet) {
+            channel.extend(samples);
+        }
+    }
+
+    Ok(Wave { header, data })
+}
+
+/// Decodes a WAV's samples to the crate's normalized `[-1, 1]` `f32`
+/// convention regardless of the file's underlying sample format, so callers
+/// don't have to match on bit depth themselves. Used both by `load_wav`
+/// (which collects the whole thing) and by anything streaming samples a
+/// block at a time.
+pub enum NormalizedSamples<'a, R> {
+    Float(hound::WavSamples<'a, R, f32>),
+    I8(hound::WavSamples<'a, R, i8>),
+    I16(hound::WavSamples<'a, R, i16>),
+    I24(hound::WavSamples<'a, R, i32>),
+    I32(hound::WavSamples<'a, R, i32>),
+}
+
+impl<'a, R: std::io::Read> Iterator for NormalizedSamples<'a, R> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        match self {
+            NormalizedSamples::Float(it) => it.next().map(|s| s.unwrap()),
+            NormalizedSamples::I8(it) => it.next().map(|s| s.unwrap() as f32 / i8::MAX as f32),
+            NormalizedSamples::I16(it) => it.next().map(|s| s.unwrap() as f32 / i16::MAX as f32),
+            NormalizedSamples::I24(it) => it.next().map(|s| s.unwrap() as f32 / 0x7FFFFF as f32),
+            NormalizedSamples::I32(it) => it.next().map(|s| s.unwrap() as f32 / i32::MAX as f32),
+        }
+    }
+}
+
+pub fn normalized_samples<R: std::io::Read>(
+    reader: &mut hound::WavReader<R>,
+) -> Result<NormalizedSamples<'_, R>, Box<dyn Error>> {
+    let spec = reader.spec();
+    Ok(match spec.sample_format {
+        hound::SampleFormat::Float => NormalizedSamples::Float(reader.samples::<f32>()),
+        hound::SampleFormat::Int => match spec.bits_per_sample {
+            8 => NormalizedSamples::I8(reader.samples::<i8>()),
+            16 => NormalizedSamples::I16(reader.samples::<i16>()),
+            24 => NormalizedSamples::I24(reader.samples::<i32>()),
+            32 => NormalizedSamples::I32(reader.samples::<i32>()),
+            _ => {
+                return Err(format!("Unrecognised bit depth: got {}", spec.bits_per_sample).into())
+            }
+        },
+    })
+}
+
+/// The inverse of `normalized_samples`: writes a single `[-1, 1]`-normalized
+/// sample out in whatever format `spec` describes.
+pub fn write_sample<W: std::io::Write + std::io::Seek>(
+    writer: &mut hound::WavWriter<W>,
+    spec: &hound::WavSpec,
+    sample: f32,
+) -> Result<(), Box<dyn Error>> {
+    match spec.sample_format {
+        hound::SampleFormat::Float => writer.write_sample(sample)?,
+        hound::SampleFormat::Int => match spec.bits_per_sample {
+            8 => writer.write_sample((sample * i8::MAX as f32) as i8)?,
+            16 => writer.write_sample((sample * i16::MAX as f32) as i16)?,
+            24 => writer.write_sample((sample * 0x7FFFFF as f32) as i32)?,
+            32 => writer.write_sample((sample * i32::MAX as f32) as i32)?,
+            _ => {
+                return Err(format!("Unrecognised bit depth: got {}", spec.bits_per_sample).into())
+            }
+        },
+    };
+    Ok(())
+}
+
+fn load_wav(path: &str) -> Result<Wave, Box<dyn Error>> {
     let mut r = hound::WavReader::open(path)?;
     let spec = r.spec();
 
@@ -36,33 +164,7 @@ pub fn load(path: &str) -> Result<Wave, Box<dyn Error>> {
         },
     };
 
-    let interleaved_data: Vec<f32> = match spec.sample_format {
-        hound::SampleFormat::Float => r.samples::<f32>().map(|s| s.unwrap()).collect(),
-
-        hound::SampleFormat::Int => match spec.bits_per_sample {
-            8 => r
-                .samples::<i8>()
-                .map(|s| (s.unwrap() as f32 / i8::MAX as f32))
-                .collect(),
-            16 => r
-                .samples::<i16>()
-                .map(|s| s.unwrap() as f32 / i16::MAX as f32)
-                .collect(),
-
-            24 => r
-                .samples::<i32>()
-                .map(|s| s.unwrap() as f32 / 0x7FFFFF as f32)
-                .collect(),
-
-            32 => r
-                .samples::<i32>()
-                .map(|s| s.unwrap() as f32 / i32::MAX as f32)
-                .collect(),
-            _ => {
-                return Err(format!("Unrecognised bit depth: got {}", spec.bits_per_sample).into())
-            }
-        },
-    };
+    let interleaved_data: Vec<f32> = normalized_samples(&mut r)?.collect();
 
     Ok(Wave {
         header,
@@ -83,22 +185,8 @@ pub fn export(path: &str, wave: Wave) -> Result<(), Box<dyn Error>> {
 
     let mut w = hound::WavWriter::create(path, spec)?;
 
-    // TODO: figure out if there's a more efficient way to do this, not nice to have to match every sample
     for s in interleave(wave.data)? {
-        match wave.header.format {
-            Format::Float => w.write_sample(s)?,
-            Format::Int => match wave.header.bit_depth {
-                8 => w.write_sample((s * i8::MAX as f32) as i8)?,
-                16 => w.write_sample((s * i16::MAX as f32) as i16)?,
-                24 => w.write_sample((s * 0x7FFFFF as f32) as i32)?,
-                32 => w.write_sample((s * i32::MAX as f32) as i32)?,
-                _ => {
-                    return Err(
-                        format!("Unrecognised bit depth: got {}", spec.bits_per_sample).into(),
-                    )
-                }
-            },
-        };
+        write_sample(&mut w, &spec, s)?;
     }
 
     w.finalize()?;
@@ -106,41 +194,125 @@ pub fn export(path: &str, wave: Wave) -> Result<(), Box<dyn Error>> {
 }
 
 fn interleave(input: Vec<Vec<f32>>) -> Result<Vec<f32>, Box<dyn Error>> {
-    match input.len() {
-        1 => Ok(input[0].clone()),
-        2 => {
-            let mut out = Vec::with_capacity(2 * input[0].len());
-            for frame in input[0].iter().zip(input[1].iter()) {
-                out.push(*frame.0);
-                out.push(*frame.1);
-            }
-            Ok(out)
+    if input.is_empty() {
+        return Err(format!("Unsupported number of channels ({})", input.len()).into());
+    }
+
+    let num_frames = input[0].len();
+    if input.iter().any(|channel| channel.len() != num_frames) {
+        return Err("All channels must have the same number of frames".into());
+    }
+
+    let mut out = Vec::with_capacity(input.len() * num_frames);
+    for frame in 0..num_frames {
+        for channel in input.iter() {
+            out.push(channel[frame]);
         }
-        _ => return Err(format!("Unsupported number of channels ({})", input.len()).into()),
     }
+    Ok(out)
 }
 
 fn uninterleave(input: Vec<f32>, channels: u16) -> Result<Vec<Vec<f32>>, Box<dyn Error>> {
-    match channels {
-        1 => Ok(vec![input]),
-        2 => {
-            let mut out = vec![
-                Vec::with_capacity(input.len() / 2),
-                Vec::with_capacity(input.len() / 2),
-            ];
-            for frame in input.chunks(2) {
-                out[0].push(frame[0]);
-                out[1].push(frame[1]);
+    if channels == 0 {
+        return Err(format!("Unsupported number of channels ({})", channels).into());
+    }
+
+    let channels = channels as usize;
+    let mut out = vec![Vec::with_capacity(input.len() / channels); channels];
+    for frame in input.chunks(channels) {
+        for (channel, sample) in out.iter_mut().zip(frame.iter()) {
+            channel.push(*sample);
+        }
+    }
+    Ok(out)
+}
+
+/// A way to remap a set of input channels onto a (possibly different) set of
+/// output channels, used to downmix/reorder a `Wave`'s data before stretching.
+#[derive(Debug, Clone)]
+pub enum RemixOp {
+    /// `coeffs[out][in]`: output channel `out` is `sum(src[in] * coeffs[out][in])`
+    Matrix(Vec<Vec<f32>>),
+    /// output channel `i` is a copy of input channel `indices[i]`
+    Reorder(Vec<usize>),
+}
+
+/// Apply a `RemixOp` to planar channel data, producing planar data for the
+/// (possibly different) number of output channels the op describes.
+pub fn remix(input: &[Vec<f32>], op: &RemixOp) -> Result<Vec<Vec<f32>>, Box<dyn Error>> {
+    match op {
+        RemixOp::Matrix(coeffs) => {
+            let num_frames = input.first().map_or(0, |channel| channel.len());
+            let mut out = vec![Vec::with_capacity(num_frames); coeffs.len()];
+
+            for (out_channel, row) in coeffs.iter().enumerate() {
+                if row.len() != input.len() {
+                    return Err(format!(
+                        "remix matrix row {} has {} coefficients, expected {} (input channels)",
+                        out_channel,
+                        row.len(),
+                        input.len()
+                    )
+                    .into());
+                }
+
+                for frame in 0..num_frames {
+                    out[out_channel]
+                        .push(row.iter().zip(input.iter()).map(|(c, src)| c * src[frame]).sum());
+                }
             }
             Ok(out)
         }
-        _ => return Err(format!("Unsupported number of channels ({})", input.len()).into()),
+        RemixOp::Reorder(indices) => indices
+            .iter()
+            .map(|&i| {
+                input.get(i).cloned().ok_or_else(|| {
+                    format!(
+                        "reorder index {} out of range ({} input channels)",
+                        i,
+                        input.len()
+                    )
+                    .into()
+                })
+            })
+            .collect(),
+    }
+}
+
+/// Commonly-used remix presets, expressed as `RemixOp`s.
+pub mod remix_presets {
+    use super::RemixOp;
+
+    /// Leaves `channels` input channels untouched.
+    pub fn passthrough(channels: usize) -> RemixOp {
+        RemixOp::Reorder((0..channels).collect())
+    }
+
+    /// Stereo down to mono by averaging the two channels.
+    pub fn stereo_to_mono() -> RemixOp {
+        RemixOp::Matrix(vec![vec![0.5, 0.5]])
+    }
+
+    /// Mono up to stereo by duplicating the single channel.
+    pub fn mono_to_stereo() -> RemixOp {
+        RemixOp::Matrix(vec![vec![1_f32], vec![1_f32]])
+    }
+
+    /// 5.1 (L, R, C, LFE, Ls, Rs) down to stereo using the usual ITU-ish
+    /// center/surround downmix coefficients (LFE is dropped).
+    pub fn surround_5_1_to_stereo() -> RemixOp {
+        let center = std::f32::consts::FRAC_1_SQRT_2;
+        let surround = std::f32::consts::FRAC_1_SQRT_2;
+        RemixOp::Matrix(vec![
+            vec![1_f32, 0_f32, center, 0_f32, surround, 0_f32],
+            vec![0_f32, 1_f32, center, 0_f32, 0_f32, surround],
+        ])
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{interleave, uninterleave};
+    use super::{interleave, remix, uninterleave, RemixOp};
 
     #[test]
     fn interleave_empty() {
@@ -168,7 +340,22 @@ mod tests {
 
     #[test]
     fn interleave_multichannel() {
-        assert!(interleave(vec![vec![], vec![], vec![]]).is_err());
+        let v = vec![
+            vec![0_f32, 1_f32],
+            vec![2_f32, 3_f32],
+            vec![4_f32, 5_f32],
+        ];
+        let interleaved = interleave(v);
+        assert!(interleaved.is_ok());
+        assert_eq!(
+            interleaved.unwrap(),
+            vec![0_f32, 2_f32, 4_f32, 1_f32, 3_f32, 5_f32]
+        );
+    }
+
+    #[test]
+    fn interleave_mismatched_lengths() {
+        assert!(interleave(vec![vec![0_f32, 1_f32], vec![2_f32]]).is_err());
     }
 
     #[test]
@@ -196,6 +383,40 @@ mod tests {
 
     #[test]
     fn uninterleave_multichannel() {
-        assert!(uninterleave(vec![], 3).is_err());
+        let v = vec![0_f32, 2_f32, 4_f32, 1_f32, 3_f32, 5_f32];
+        let uninterleaved = uninterleave(v, 3);
+        assert!(uninterleaved.is_ok());
+        assert_eq!(
+            uninterleaved.unwrap(),
+            vec![vec![0_f32, 1_f32], vec![2_f32, 3_f32], vec![4_f32, 5_f32]]
+        );
+    }
+
+    #[test]
+    fn remix_matrix_downmix() {
+        let v = vec![vec![0_f32, 2_f32], vec![1_f32, 3_f32]];
+        let out = remix(&v, &RemixOp::Matrix(vec![vec![0.5, 0.5]]));
+        assert!(out.is_ok());
+        assert_eq!(out.unwrap(), vec![vec![0.5_f32, 2.5_f32]]);
+    }
+
+    #[test]
+    fn remix_matrix_wrong_row_len() {
+        let v = vec![vec![0_f32], vec![1_f32]];
+        assert!(remix(&v, &RemixOp::Matrix(vec![vec![1_f32]])).is_err());
+    }
+
+    #[test]
+    fn remix_reorder() {
+        let v = vec![vec![0_f32, 1_f32], vec![2_f32, 3_f32]];
+        let out = remix(&v, &RemixOp::Reorder(vec![1, 0]));
+        assert!(out.is_ok());
+        assert_eq!(out.unwrap(), vec![vec![2_f32, 3_f32], vec![0_f32, 1_f32]]);
+    }
+
+    #[test]
+    fn remix_reorder_out_of_range() {
+        let v = vec![vec![0_f32]];
+        assert!(remix(&v, &RemixOp::Reorder(vec![5])).is_err());
     }
 }